@@ -2,11 +2,15 @@ use std::cast;
 use std::logging;
 use std::ptr;
 use std::str::raw::from_c_str;
-use std::task;
+use std::unstable::atomics::{AtomicUint, SeqCst};
+
+use extra::json;
 
 use gtk::*;
 use gtk::ffi::*;
 
+use ws;
+
 use gui;
 
 struct ClockIDWrapper {
@@ -28,6 +32,15 @@ impl Drop for ClockIDWrapper {
     }
 }
 
+// User data for the bus watch. Boxed so its address stays valid for gstreamer.
+struct BusContext {
+    gui: *gui::Gui,
+    playbin: *mut GstElement,
+    // Mirror of the user's intended `playing` state, kept here (not as a
+    // pointer into the movable `Player`) so buffering can resume safely.
+    playing: bool,
+}
+
 struct Player {
     initialized: bool,
 
@@ -35,8 +48,47 @@ struct Player {
     playing: bool,
 
     playbin: *mut GstElement,
+    bus_ctx: Option<~BusContext>,
+    atf_ctx: Option<~AboutToFinishCtx>,
     report_clock_id: Option<ClockIDWrapper>,
     progress_clock_id: Option<ClockIDWrapper>,
+    report_watch: Option<~ReportWatch>,
+    progress_watch: Option<~ProgressWatch>,
+
+    // Opt-in live transcription (see `Player::set_transcription`). Off by
+    // default, so users without a recognizer server pay nothing.
+    transcribe: bool,
+    transcription_server: ~str,
+    tap: Option<~TranscriptionTap>,
+}
+
+// Confidence below which a recognized word is dropped from a final phrase.
+static TRANSCRIPTION_CONF_THRESHOLD: f64 = 0.8;
+
+// Handle on the transcription tap; holds the appsink the worker pulls from.
+struct TranscriptionTap {
+    appsink: *mut GstAppSink,
+}
+
+// User data for the async clock callbacks. Carry the stable `gui` pointer so
+// the callbacks reach the channel without naming its element type.
+struct ReportWatch {
+    gui: *gui::Gui,
+}
+
+struct ProgressWatch {
+    gui: *gui::Gui,
+    playbin: *mut GstElement,
+}
+
+// User data for playbin's `about-to-finish` signal.
+// `next_uri` is handed across threads as a raw `~~str` pointer (0 = none):
+// `set_next_uri` swaps one in from the gui thread, `about_to_finish` swaps it
+// out on the streaming thread, so neither races on the owned string.
+struct AboutToFinishCtx {
+    gui: *gui::Gui,
+    playbin: *mut GstElement,
+    next_uri: AtomicUint,
 }
 
 impl Player {
@@ -46,8 +98,15 @@ impl Player {
             uri_set: false,
             playing: false,
             playbin: ptr::mut_null(),
+            bus_ctx: None,
+            atf_ctx: None,
             report_clock_id: None,
             progress_clock_id: None,
+            report_watch: None,
+            progress_watch: None,
+            transcribe: false,
+            transcription_server: ~"ws://localhost:2700",
+            tap: None,
         }
     }
 
@@ -68,14 +127,158 @@ impl Player {
                 fail!("failed to create playbin");
             }
 
+            self.bus_ctx = Some(~BusContext {
+                gui: gui as *gui::Gui,
+                playbin: self.playbin,
+                playing: self.playing,
+            });
+            let ctx: &BusContext = *self.bus_ctx.get_ref();
             let bus = gst_pipeline_get_bus(cast::transmute(self.playbin));
             gst_bus_add_watch(bus, bus_callback,
-                              cast::transmute::<&gui::Gui, gpointer>(gui));
+                              cast::transmute::<&BusContext, gpointer>(ctx));
+
+            // Gapless: set the next uri from `about_to_finish`; EOS stays as
+            // the fallback for the last track.
+            self.atf_ctx = Some(~AboutToFinishCtx {
+                gui: gui as *gui::Gui,
+                playbin: self.playbin,
+                next_uri: AtomicUint::new(0),
+            });
+            let atf: &AboutToFinishCtx = *self.atf_ctx.get_ref();
+            "about-to-finish".with_c_str(|signal_c_str| {
+                g_signal_connect(cast::transmute(self.playbin), signal_c_str,
+                                 cast::transmute(about_to_finish),
+                                 cast::transmute::<&AboutToFinishCtx, gpointer>(atf));
+            });
+
+            if self.transcribe {
+                self.setup_transcription(gui);
+            }
         }
         self.initialized = true;
         args2
     }
 
+    // Enable the live transcription tap. Must be called before `init`.
+    // `server` is the recognizer websocket address.
+    pub fn set_transcription(&mut self, enabled: bool, server: ~str) {
+        self.transcribe = enabled;
+        self.transcription_server = server;
+    }
+
+    // Swap playbin's audio-sink for a tee: one branch plays, the other converts
+    // to S16 mono PCM and feeds an appsink we forward to the recognizer.
+    unsafe fn setup_transcription(&mut self, gui: &gui::Gui) {
+        let bin = gst_bin_new(ptr::null());
+
+        let make = |factory: &str, name: &str| -> *mut GstElement {
+            let mut elem = ptr::mut_null();
+            factory.with_c_str(|f| {
+                name.with_c_str(|n| {
+                    elem = gst_element_factory_make(f, n);
+                });
+            });
+            if elem.is_null() {
+                fail!("failed to create transcription element `{}`", factory);
+            }
+            gst_bin_add(cast::transmute(bin), elem);
+            elem
+        };
+
+        let tee = make("tee", "rusttracks-tee");
+        let play_queue = make("queue", "rusttracks-play-queue");
+        let audiosink = make("autoaudiosink", "rusttracks-audiosink");
+        let tap_queue = make("queue", "rusttracks-tap-queue");
+        let convert = make("audioconvert", "rusttracks-convert");
+        let resample = make("audioresample", "rusttracks-resample");
+        let capsfilter = make("capsfilter", "rusttracks-caps");
+        let appsink = make("appsink", "rusttracks-appsink");
+
+        // Fixed S16 mono PCM at 16kHz, which is what the recognizer expects.
+        "audio/x-raw,format=S16LE,channels=1,rate=16000".with_c_str(|caps_str| {
+            let caps = gst_caps_from_string(caps_str);
+            "caps".with_c_str(|prop| {
+                g_object_set(cast::transmute(capsfilter), prop, caps, ptr::null::<gchar>());
+            });
+            gst_caps_unref(caps);
+        });
+
+        gst_element_link(tee, play_queue);
+        gst_element_link(play_queue, audiosink);
+        gst_element_link(tee, tap_queue);
+        gst_element_link(tap_queue, convert);
+        gst_element_link(convert, resample);
+        gst_element_link(resample, capsfilter);
+        gst_element_link(capsfilter, appsink);
+
+        // A ghost pad so the bin can stand in as playbin's audio-sink.
+        let sink_pad = "sink".with_c_str(|p| gst_element_get_static_pad(tee, p));
+        let ghost = "sink".with_c_str(|p| gst_ghost_pad_new(p, sink_pad));
+        gst_element_add_pad(bin, ghost);
+        gst_object_unref(cast::transmute(sink_pad));
+
+        // A single worker task pulls from the appsink (on a Rust task, so the
+        // channel send has a runtime context) and owns the websocket client.
+        let appsink: *mut GstAppSink = cast::transmute(appsink);
+        let server = self.transcription_server.clone();
+        let gui_chan = gui.get_chan().clone();
+        spawn(proc() {
+            let mut sock = ws::Client::connect(server);
+            let mut phrase = ~"";
+            loop {
+                let sample = unsafe { gst_app_sink_pull_sample(appsink) };
+                if sample.is_null() {
+                    break; // appsink flushed / EOS; wind down.
+                }
+                let (pts, pcm) = unsafe {
+                    let buffer = gst_sample_get_buffer(sample);
+                    // GST_CLOCK_TIME_NONE means no valid timestamp.
+                    let raw_pts = (*buffer).pts;
+                    let pts = if raw_pts == GST_CLOCK_TIME_NONE {
+                        -1
+                    } else {
+                        raw_pts as i64
+                    };
+                    let size = gst_buffer_get_size(buffer) as uint;
+                    let mut pcm = ::std::slice::from_elem(size, 0u8);
+                    gst_buffer_extract(buffer, 0, pcm.as_mut_ptr() as gpointer, size as gsize);
+                    gst_sample_unref(sample);
+                    (pts, pcm)
+                };
+
+                sock.send_binary(pcm);
+                loop {
+                    match sock.try_recv_text() {
+                        Some(reply) => {
+                            match assemble_caption(reply, &mut phrase) {
+                                Some(caption) => {
+                                    // Stamp with the buffer position when known.
+                                    let stamped = if pts < 0 {
+                                        caption
+                                    } else {
+                                        let secs = pts / 1_000_000_000;
+                                        format!("[{:02}:{:02}] {}",
+                                            secs / 60, secs % 60, caption)
+                                    };
+                                    gui_chan.send(gui::SetCaption(stamped));
+                                }
+                                None => {}
+                            }
+                        }
+                        None => break
+                    }
+                }
+            }
+            sock.close();
+        });
+
+        self.tap = Some(~TranscriptionTap { appsink: appsink });
+
+        "audio-sink".with_c_str(|prop| {
+            g_object_set(cast::transmute(self.playbin), prop, bin, ptr::null::<gchar>());
+        });
+    }
+
     pub fn set_uri(&mut self, uri: &str, gui: &gui::Gui) {
         self.stop();
         unsafe {
@@ -93,10 +296,36 @@ impl Player {
         self.uri_set = true;
     }
 
+    // Prime the uri to roll into when the current track is about to finish.
+    // The GUI calls this ahead of the transition; the gapless callback picks it
+    // up without a round-trip.
+    pub fn set_next_uri(&mut self, uri: ~str) {
+        match self.atf_ctx {
+            Some(ref ctx) => unsafe {
+                let boxed: ~~str = ~uri;
+                let prev = ctx.next_uri.swap(cast::transmute(boxed), SeqCst);
+                if prev != 0 {
+                    // Drop a uri the callback never consumed.
+                    let _: ~~str = cast::transmute(prev);
+                }
+            },
+            None => {}
+        }
+    }
+
+    // Re-arm the watches after a gapless transition; `set_uri` is bypassed, so
+    // the gui calls this when it handles `gui::GaplessAdvanced`.
+    pub fn rearm_watches(&mut self, gui: &gui::Gui) {
+        self.report_clock_id = None;
+        self.progress_clock_id = None;
+        self.report_watch = None;
+        self.progress_watch = None;
+        self.start_report_watch(gui);
+        self.start_progress_watch(gui);
+    }
+
     fn start_report_watch(&mut self, gui: &gui::Gui) {
-        let chan = gui.get_chan().clone();
         unsafe {
-
             let clock = gst_pipeline_get_clock(cast::transmute(self.playbin));
 
             // in nanoseconds
@@ -106,26 +335,18 @@ impl Player {
             let ci = gst_clock_new_single_shot_id(clock, target_time);
             self.report_clock_id = Some(ClockIDWrapper::new(ci));
 
-            do task::spawn_sched(task::SingleThreaded) {
-                let res = gst_clock_id_wait(ci, ptr::mut_null());
-                match res {
-                    GST_CLOCK_UNSCHEDULED => { } // Ignore, nothing to do
-                    GST_CLOCK_OK => {
-                        debug!("30s are up! sending ReportCurrentTrack to gui");
-                        chan.send(gui::ReportCurrentTrack);
-                    }
-                    _ => unreachable!()
-                }
-            }
+            // Async: the clock thread invokes `report_wait_cb` on expiry.
+            self.report_watch = Some(~ReportWatch { gui: gui as *gui::Gui });
+            let watch: &ReportWatch = *self.report_watch.get_ref();
+            gst_clock_id_wait_async(ci, report_wait_cb,
+                cast::transmute::<&ReportWatch, gpointer>(watch));
 
             gst_object_unref(cast::transmute(clock));
         }
     }
 
     fn start_progress_watch(&mut self, gui: &gui::Gui) {
-        let chan = gui.get_chan().clone();
         unsafe {
-
             let clock = gst_pipeline_get_clock(cast::transmute(self.playbin));
 
             // in nanoseconds
@@ -135,34 +356,14 @@ impl Player {
             let ci = gst_clock_new_periodic_id(clock, target_time, period);
             self.progress_clock_id = Some(ClockIDWrapper::new(ci));
 
-            let playbin = self.playbin;
-            do task::spawn_sched(task::SingleThreaded) {
-                loop {
-                    let res = gst_clock_id_wait(ci, ptr::mut_null());
-                    match res {
-                        GST_CLOCK_UNSCHEDULED => {
-                            // Track has ended or whatever, stop polling
-                            break;
-                        }
-                        GST_CLOCK_OK => {
-                            debug!("1s is up! sending progress");
-                            let mut current_position = 0;
-                            let success_position = gst_element_query_position(
-                                playbin, GST_FORMAT_TIME, &mut current_position);
-                            let mut current_duration = 0;
-                            let success_duration = gst_element_query_duration(
-                                playbin, GST_FORMAT_TIME, &mut current_duration);
-
-                            if success_duration != 0 && success_position != 0 {
-                                chan.send(gui::SetProgress(Some((current_position, current_duration))));
-                            } else {
-                                chan.send(gui::SetProgress(None));
-                            }
-                        }
-                        _ => unreachable!()
-                    }
-                }
-            }
+            // Periodic id: gstreamer re-fires `progress_wait_cb` every second.
+            self.progress_watch = Some(~ProgressWatch {
+                gui: gui as *gui::Gui,
+                playbin: self.playbin,
+            });
+            let watch: &ProgressWatch = *self.progress_watch.get_ref();
+            gst_clock_id_wait_async(ci, progress_wait_cb,
+                cast::transmute::<&ProgressWatch, gpointer>(watch));
 
             gst_object_unref(cast::transmute(clock));
         }
@@ -179,7 +380,7 @@ impl Player {
         unsafe {
             gst_element_set_state(self.playbin, GST_STATE_PLAYING);
         }
-        self.playing = true;
+        self.set_playing(true);
     }
 
     pub fn pause(&mut self) {
@@ -189,20 +390,79 @@ impl Player {
         unsafe {
             gst_element_set_state(self.playbin, GST_STATE_PAUSED);
         }
-        self.playing = false;
+        self.set_playing(false);
+    }
+
+    // Record playback intent, mirroring it into the bus context for buffering.
+    fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+        match self.bus_ctx {
+            Some(ref mut ctx) => ctx.playing = playing,
+            None => {}
+        }
     }
 
     pub fn stop(&mut self) {
         if !self.initialized {
             fail!("player is not initialized");
         }
+        // Unschedule the async waits before freeing their user data, so the
+        // clock thread can't call back into a dropped `ReportWatch`/`ProgressWatch`.
         self.report_clock_id = None;
         self.progress_clock_id = None;
+        self.report_watch = None;
+        self.progress_watch = None;
         unsafe{
             gst_element_set_state(self.playbin, GST_STATE_READY);
         }
         self.uri_set = false;
-        self.playing = false;
+        self.set_playing(false);
+    }
+
+    // playbin's `volume` is linear (1.0 = unity), clamped here to [0.0, 10.0].
+    pub fn set_volume(&mut self, level: f64) {
+        if !self.initialized {
+            fail!("player is not initialized");
+        }
+        let clamped = if level < 0.0 {
+            0.0
+        } else if level > 10.0 {
+            10.0
+        } else {
+            level
+        };
+        unsafe {
+            "volume".with_c_str(|property_c_str| {
+                g_object_set(cast::transmute(self.playbin),
+                    property_c_str, clamped as gdouble, ptr::null::<gchar>());
+            });
+        }
+    }
+
+    pub fn get_volume(&self) -> f64 {
+        if !self.initialized {
+            fail!("player is not initialized");
+        }
+        let mut level: gdouble = 0.0;
+        unsafe {
+            "volume".with_c_str(|property_c_str| {
+                g_object_get(cast::transmute(self.playbin),
+                    property_c_str, &mut level, ptr::null::<gchar>());
+            });
+        }
+        level as f64
+    }
+
+    pub fn set_mute(&mut self, mute: bool) {
+        if !self.initialized {
+            fail!("player is not initialized");
+        }
+        unsafe {
+            "mute".with_c_str(|property_c_str| {
+                g_object_set(cast::transmute(self.playbin),
+                    property_c_str, mute as gboolean, ptr::null::<gchar>());
+            });
+        }
     }
 
     pub fn toggle(&mut self) {
@@ -224,6 +484,18 @@ impl Player {
 
 impl Drop for Player {
     fn drop(&mut self) {
+        // Free a primed-but-unconsumed next uri.
+        match self.atf_ctx {
+            Some(ref ctx) => {
+                let raw = ctx.next_uri.swap(0, SeqCst);
+                if raw != 0 {
+                    let _: ~~str = unsafe { cast::transmute(raw) };
+                }
+            }
+            None => {}
+        }
+        // Setting the pipeline to GST_STATE_NULL below ends the worker task,
+        // whose `gst_app_sink_pull_sample` then returns null.
         if self.initialized {
             unsafe {
                 if !self.playbin.is_null() {
@@ -236,9 +508,171 @@ impl Drop for Player {
     }
 }
 
+// The `*_wait_cb` functions run on GStreamer's clock thread, which is not a
+// Rust task, so they must not touch the gui channel directly. They bounce the
+// work onto the GLib main context via `g_idle_add` — the single shared
+// execution context where the bus watch already runs as a Rust task — and the
+// `*_idle` functions do the actual send from there.
+// The clock-thread callbacks hand the idle its own payload box (holding only
+// program-stable pointers), so it survives even if `stop()` frees the watch
+// box before the idle runs. The `*_idle` reclaims and frees that box.
+extern "C" fn report_wait_cb(_clock: *mut GstClock, _time: GstClockTime,
+                             _id: GstClockID, user_data: gpointer) -> gboolean {
+    unsafe {
+        let watch: &ReportWatch = cast::transmute(user_data);
+        let payload: ~ReportWatch = ~ReportWatch { gui: watch.gui };
+        g_idle_add(report_idle, cast::transmute(payload));
+    }
+    // Single-shot id: returning does not reschedule it.
+    1
+}
+
+extern "C" fn report_idle(user_data: gpointer) -> gboolean {
+    unsafe {
+        let payload: ~ReportWatch = cast::transmute(user_data);
+        let gui: &gui::Gui = cast::transmute(payload.gui);
+        debug!("30s are up! sending ReportCurrentTrack to gui");
+        gui.get_chan().send(gui::ReportCurrentTrack);
+    }
+    // Remove this idle source after it fires once.
+    0
+}
+
+extern "C" fn progress_wait_cb(_clock: *mut GstClock, _time: GstClockTime,
+                               _id: GstClockID, user_data: gpointer) -> gboolean {
+    unsafe {
+        let watch: &ProgressWatch = cast::transmute(user_data);
+        let payload: ~ProgressWatch = ~ProgressWatch {
+            gui: watch.gui,
+            playbin: watch.playbin,
+        };
+        g_idle_add(progress_idle, cast::transmute(payload));
+    }
+    // Periodic id: gstreamer keeps firing until it is unscheduled.
+    1
+}
+
+extern "C" fn progress_idle(user_data: gpointer) -> gboolean {
+    unsafe {
+        let payload: ~ProgressWatch = cast::transmute(user_data);
+        let gui: &gui::Gui = cast::transmute(payload.gui);
+
+        let mut current_position = 0;
+        let success_position = gst_element_query_position(
+            payload.playbin, GST_FORMAT_TIME, &mut current_position);
+        let mut current_duration = 0;
+        let success_duration = gst_element_query_duration(
+            payload.playbin, GST_FORMAT_TIME, &mut current_duration);
+
+        if success_duration != 0 && success_position != 0 {
+            gui.get_chan().send(gui::SetProgress(Some((current_position, current_duration))));
+        } else {
+            gui.get_chan().send(gui::SetProgress(None));
+        }
+    }
+    // Remove this idle source; the periodic clock id schedules a fresh one.
+    0
+}
+
+// Feed one recognizer reply into `phrase`. A `partial` emits the growing phrase
+// live; a `result` emits the words at or above `TRANSCRIPTION_CONF_THRESHOLD`
+// and clears it. `None` means nothing new to show.
+fn assemble_caption(reply: ~str, phrase: &mut ~str) -> Option<~str> {
+    let parsed = match json::from_str(reply) {
+        Ok(j) => j,
+        Err(_) => return None,
+    };
+    let obj = match parsed {
+        json::Object(o) => o,
+        _ => return None,
+    };
+
+    match obj.find(&~"result") {
+        Some(&json::List(ref words)) => {
+            let mut kept: ~[~str] = ~[];
+            for w in words.iter() {
+                match *w {
+                    json::Object(ref wo) => {
+                        let conf = match wo.find(&~"conf") {
+                            Some(&json::Number(c)) => c,
+                            _ => 0.0,
+                        };
+                        if conf >= TRANSCRIPTION_CONF_THRESHOLD {
+                            match wo.find(&~"word") {
+                                Some(&json::String(ref s)) => kept.push(s.clone()),
+                                _ => {}
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            *phrase = ~"";
+            if kept.is_empty() {
+                None
+            } else {
+                Some(kept.connect(" "))
+            }
+        }
+        _ => {
+            // Interim partial: emit the rolling phrase whenever it actually
+            // changes, so the caption tracks the recognizer live without
+            // re-sending an identical string.
+            match obj.find(&~"partial") {
+                Some(&json::String(ref s)) => {
+                    if s.is_empty() || *s == *phrase {
+                        None
+                    } else {
+                        *phrase = s.clone();
+                        Some(phrase.clone())
+                    }
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+extern "C" fn about_to_finish(_playbin: *mut GstElement, data: gpointer) {
+    unsafe {
+    // On playbin's streaming thread: don't touch the channel or block here.
+    let ctx: &AboutToFinishCtx = cast::transmute(data);
+
+    let raw = ctx.next_uri.swap(0, SeqCst);
+    if raw != 0 {
+        let uri: ~~str = cast::transmute(raw);
+        // Set synchronously so playbin prerolls the next stream with no gap.
+        debug!("about-to-finish: prerolling next uri `{}`", *uri);
+        "uri".with_c_str(|property_c_str| {
+            uri.with_c_str(|uri_c_str| {
+                g_object_set(cast::transmute(ctx.playbin),
+                    property_c_str, uri_c_str, ptr::null::<gchar>());
+            });
+        });
+        // Hop onto the main context to notify the gui, which re-arms the watches.
+        g_idle_add(gapless_advanced_idle,
+                   cast::transmute::<&AboutToFinishCtx, gpointer>(ctx));
+    } else {
+        // Nothing primed; let the stream end and EOS handle the last track.
+        debug!("about-to-finish: no next track, leaving EOS as fallback");
+    }
+    }
+}
+
+extern "C" fn gapless_advanced_idle(user_data: gpointer) -> gboolean {
+    unsafe {
+        let ctx: &AboutToFinishCtx = cast::transmute(user_data);
+        let gui: &gui::Gui = cast::transmute(ctx.gui);
+        gui.get_chan().send(gui::GaplessAdvanced);
+    }
+    // Remove this idle source after it fires once.
+    0
+}
+
 extern "C" fn bus_callback(_bus: *mut GstBus, msg: *mut GstMessage, data: gpointer) -> gboolean {
     unsafe {
-    let gui: &gui::Gui = cast::transmute(data);
+    let ctx: &BusContext = cast::transmute(data);
+    let gui: &gui::Gui = cast::transmute(ctx.gui);
 
     let name = {
         let gst_obj = (*msg).src;
@@ -303,6 +737,22 @@ extern "C" fn bus_callback(_bus: *mut GstBus, msg: *mut GstMessage, data: gpoint
                 g_free(cast::transmute(dbg_info));
             }
         }
+        GST_MESSAGE_BUFFERING => {
+            let mut percent: gint = 0;
+            gst_message_parse_buffering(msg, &mut percent);
+            debug!("buffering {}% from element {}", percent, name);
+            if percent < 100 {
+                // Hold while refilling, without touching the `playing` flag.
+                gst_element_set_state(ctx.playbin, GST_STATE_PAUSED);
+                gui.get_chan().send(gui::SetBuffering(Some(percent as int)));
+            } else {
+                // Full again; resume only if the user still wants playback.
+                if ctx.playing {
+                    gst_element_set_state(ctx.playbin, GST_STATE_PLAYING);
+                }
+                gui.get_chan().send(gui::SetBuffering(None));
+            }
+        }
         GST_MESSAGE_EOS => {
             debug!("EOS from element {}", name);
             gui.get_chan().send(gui::NextTrack);
@@ -320,3 +770,38 @@ extern "C" fn bus_callback(_bus: *mut GstBus, msg: *mut GstMessage, data: gpoint
     return 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble_caption, TRANSCRIPTION_CONF_THRESHOLD};
+
+    #[test]
+    fn partial_updates_phrase() {
+        let mut phrase = ~"";
+        let got = assemble_caption(~"{\"partial\": \"hello wor\"}", &mut phrase);
+        assert_eq!(got, Some(~"hello wor"));
+        assert_eq!(phrase, ~"hello wor");
+        // The same partial again emits nothing new.
+        assert_eq!(assemble_caption(~"{\"partial\": \"hello wor\"}", &mut phrase), None);
+    }
+
+    #[test]
+    fn result_filters_by_confidence() {
+        // The first word clears the threshold, the second doesn't.
+        assert!(0.5 < TRANSCRIPTION_CONF_THRESHOLD);
+        let reply = ~"{\"result\": [\
+            {\"conf\": 0.95, \"word\": \"yes\"}, \
+            {\"conf\": 0.5, \"word\": \"no\"}], \"text\": \"yes no\"}";
+        let mut phrase = ~"partial so far";
+        assert_eq!(assemble_caption(reply, &mut phrase), Some(~"yes"));
+        // A final result clears the rolling phrase.
+        assert_eq!(phrase, ~"");
+    }
+
+    #[test]
+    fn invalid_json_is_ignored() {
+        let mut phrase = ~"";
+        assert_eq!(assemble_caption(~"{not json", &mut phrase), None);
+        assert_eq!(phrase, ~"");
+    }
+}